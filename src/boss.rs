@@ -1,8 +1,14 @@
 use std::{
     collections::{HashMap, HashSet},
     future::Future,
-    process::ExitStatus,
-    time::Instant,
+    net::SocketAddr,
+    os::unix::process::ExitStatusExt,
+    path::Path,
+    pin::Pin,
+    process::{ExitStatus, Stdio},
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
 };
 
 use anyhow::Result;
@@ -14,25 +20,323 @@ use nix::{
     sys::signal::{kill, Signal},
     unistd::Pid,
 };
-use serde::{Deserialize, Deserializer};
+use notify::{DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher};
+use humantime;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json;
 use serde_yaml;
 use shellwords;
 use structopt::StructOpt;
 use tokio::{
-    process::Command,
+    fs::File,
+    io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader},
+    process::{Child, Command},
     signal::unix::{signal, SignalKind},
+    sync::{mpsc, watch},
 };
 
+mod control;
+
+/* How lifecycle events are rendered: `Text` keeps today's human-readable
+   lines, `Json` emits one `Event` per line (newline-delimited JSON) so boss
+   can be supervised by another program or shipped to a log pipeline.
+*/
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            other => Err(format!("invalid format '{}' (expected text or json)", other)),
+        }
+    }
+}
+
+/* Why a command stopped being run, carried on `Event::Stopped` so both the
+   text and JSON renderers can explain the distinct cases without the main
+   loop needing bespoke messages for each.
+*/
+#[derive(Serialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum StopReason {
+    /// Removed or replaced via a config reload, or signaled by an operator.
+    Manual,
+    /// The command's `restart` policy declined to restart it.
+    Policy,
+    /// `max_restarts` was exceeded during a crash loop.
+    GaveUp,
+}
+
+/* A command's lifecycle transitions, serialized as newline-delimited JSON
+   in `--format json` mode and rendered as today's plain text otherwise.
+   `name` identifies which command the event is about; it's `None` only for
+   process-set-wide events like `AllFinished`.
+*/
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event {
+    Started,
+    Exited {
+        code: Option<i32>,
+        signal: Option<i32>,
+        duration_secs: u64,
+        killed_by_watchdog: bool,
+    },
+    Restarting {
+        attempt: u32,
+        delay_secs: u64,
+    },
+    Stopped {
+        reason: StopReason,
+    },
+    ConfigReloaded {
+        added: Vec<String>,
+        removed: Vec<String>,
+        changed: Vec<String>,
+    },
+    AllFinished,
+}
+
+/* Renders one event either as a human-readable line or as a JSON record
+   tagged with the command name and an RFC 3339 timestamp.
+*/
+fn emit_event(format: OutputFormat, name: Option<&str>, event: Event) {
+    match format {
+        OutputFormat::Text => render_text_event(name, &event),
+        OutputFormat::Json => {
+            #[derive(Serialize)]
+            struct Record<'a> {
+                name: Option<&'a str>,
+                timestamp: String,
+                #[serde(flatten)]
+                event: &'a Event,
+            }
+            let record = Record {
+                name,
+                timestamp: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+                event: &event,
+            };
+            match serde_json::to_string(&record) {
+                Ok(line) => println!("{}", line),
+                Err(e) => eprintln!("error serializing event: {:?}", e),
+            }
+        }
+    }
+}
+
+fn render_text_event(name: Option<&str>, event: &Event) {
+    let name = name.unwrap_or("boss");
+    match event {
+        Event::Started => println!("starting {}", name),
+        Event::Exited {
+            code,
+            duration_secs,
+            killed_by_watchdog,
+            ..
+        } => {
+            let result = match code {
+                Some(code) => format!("exited with status {}", code),
+                None => "terminated by signal".to_string(),
+            };
+            println!(
+                "{}: {}, after {} sec.{}",
+                name,
+                result,
+                duration_secs,
+                if *killed_by_watchdog { " (killed by watchdog)" } else { "" },
+            );
+        }
+        Event::Restarting { attempt, delay_secs } => {
+            if *delay_secs > 0 {
+                println!(
+                    "{} exited too quickly, restarting in {} sec. (attempt {})",
+                    name, delay_secs, attempt
+                );
+            } else {
+                println!("restarting: {}", name);
+            }
+        }
+        Event::Stopped { reason } => match reason {
+            StopReason::Manual => println!("stopping {}", name),
+            StopReason::Policy => println!("not restarting {} (restart policy)", name),
+            StopReason::GaveUp => println!("giving up on {} after repeated crashes", name),
+        },
+        Event::ConfigReloaded {
+            added,
+            removed,
+            changed,
+        } => {
+            if added.is_empty() && removed.is_empty() && changed.is_empty() {
+                println!("no changes to commands");
+            } else {
+                println!(
+                    "config reloaded: added {:?}, removed {:?}, changed {:?}",
+                    added, removed, changed
+                );
+            }
+        }
+        Event::AllFinished => println!("All processes finished"),
+    }
+}
+
+/* Where a command's stdout/stderr should go. `Inherit` means boss's own
+   console, but tagged with the command name so concurrent processes'
+   output can be told apart; `Null` discards it; `File` appends each line
+   to a dedicated log, rotating the previous one aside first.
+*/
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+enum Sink {
+    Inherit,
+    Null,
+    File(String),
+}
+
+impl Default for Sink {
+    fn default() -> Self {
+        Sink::Inherit
+    }
+}
+
+/* Governs whether a finished command gets re-spawned, mirroring Docker's
+   restart policy names and semantics. `Always` and `UnlessStopped` both
+   restart regardless of exit status, but neither restarts a command that
+   was explicitly stopped (via the control API's `/stop` or a config
+   reload that drops the command) until a matching `/start` clears the
+   flag -- the two only differ for a daemon that persists restart policy
+   across its own restarts, which boss doesn't do, so here they behave
+   the same. `OnFailure` additionally requires a non-zero exit.
+*/
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+enum RestartPolicy {
+    Always,
+    OnFailure,
+    UnlessStopped,
+    Never,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy::Always
+    }
+}
+
+/* How a command signals "readiness" to commands that list it in `after`.
+   Externally tagged like `Sink`, so `ready_when: exit` deserializes the
+   unit variant and `ready_when: {tcp: "host:port"}` the newtype ones.
+*/
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "snake_case")]
+enum ReadyWhen {
+    /// Ready once the process exits cleanly; for one-shot init/migration commands.
+    Exit,
+    /// Ready once a line of its stdout matches this regex.
+    LogMatch(String),
+    /// Ready once a TCP connection to this `host:port` succeeds.
+    Tcp(String),
+}
+
+/* Below this run duration, a restart is considered a crash loop rather
+   than a normal respawn, and a new one is delayed by an escalating backoff.
+*/
+const DEFAULT_SUCCESS_THRESHOLD: Duration = Duration::from_secs(10);
+const DEFAULT_BACKOFF_BASE: Duration = Duration::from_secs(1);
+const DEFAULT_BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+/* How long a graceful shutdown (SIGINT/SIGTERM) waits for SIGTERM'd
+   commands to exit on their own before escalating to SIGKILL.
+*/
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
 /* The command specification, along with an alias for its "collection type"
 */
 #[derive(Deserialize)]
 struct CmdSpec {
     #[serde(deserialize_with = "get_argv_from_str")]
     argv: Vec<String>,
+    #[serde(default)]
+    stdout: Sink,
+    #[serde(default)]
+    stderr: Sink,
+    /// How long a run may last before the watchdog sends SIGTERM.
+    #[serde(default, with = "humantime_serde::option")]
+    timeout: Option<Duration>,
+    /// Grace period after SIGTERM before the watchdog escalates to SIGKILL.
+    #[serde(default, with = "humantime_serde::option")]
+    kill_timeout: Option<Duration>,
+    #[serde(default)]
+    restart: RestartPolicy,
+    /// Minimum run duration to count as a successful start; see `DEFAULT_SUCCESS_THRESHOLD`.
+    #[serde(default, with = "humantime_serde::option")]
+    success_threshold: Option<Duration>,
+    /// Initial and maximum delay of the crash-loop backoff; see `DEFAULT_BACKOFF_BASE`/`DEFAULT_BACKOFF_MAX`.
+    #[serde(default, with = "humantime_serde::option")]
+    backoff_base: Option<Duration>,
+    #[serde(default, with = "humantime_serde::option")]
+    backoff_max: Option<Duration>,
+    /// Give up restarting once more than this many consecutive quick failures
+    /// have happened, i.e. at most this many crash-loop restarts are attempted.
+    #[serde(default)]
+    max_restarts: Option<u32>,
+    /// Names of other commands that must become ready (see `ready_when`)
+    /// before this one is started for the first time.
+    #[serde(default)]
+    after: Vec<String>,
+    /// The condition under which this command counts as "ready" for
+    /// commands that list it in `after`. Defaults to as soon as it's
+    /// spawned, which is right for most long-running daemons.
+    #[serde(default)]
+    ready_when: Option<ReadyWhen>,
+    #[serde(skip_deserializing, default)]
+    restart_count: u32,
+    /// Set by an explicit stop (control API or config reload); cleared by start.
+    #[serde(skip_deserializing, default)]
+    manually_stopped: bool,
+    #[serde(skip_deserializing)]
+    started_at: Option<Instant>,
+    #[serde(skip_deserializing)]
+    last_exit_code: Option<i32>,
     #[serde(skip_deserializing)]
     pid: Option<Pid>,
 }
 
+impl CmdSpec {
+    fn success_threshold(&self) -> Duration {
+        self.success_threshold.unwrap_or(DEFAULT_SUCCESS_THRESHOLD)
+    }
+
+    /* Exponential backoff keyed off the current restart count: base, then
+       base*2, base*4, ..., capped at backoff_max.
+    */
+    fn backoff_delay(&self) -> Duration {
+        let base = self.backoff_base.unwrap_or(DEFAULT_BACKOFF_BASE);
+        let max = self.backoff_max.unwrap_or(DEFAULT_BACKOFF_MAX);
+        let exponent = self.restart_count.saturating_sub(1).min(16);
+        base.saturating_mul(1u32 << exponent).min(max)
+    }
+}
+
+/* Whether an exit warrants a restart under the given policy. `manually_stopped`
+   reflects an explicit stop via the control API or a config reload, which
+   `Always`, `UnlessStopped` and `OnFailure` all honor.
+*/
+fn should_restart(policy: RestartPolicy, exit_status: &ExitStatus, manually_stopped: bool) -> bool {
+    match policy {
+        RestartPolicy::Never => false,
+        RestartPolicy::Always | RestartPolicy::UnlessStopped => !manually_stopped,
+        RestartPolicy::OnFailure => !manually_stopped && !exit_status.success(),
+    }
+}
+
 type Cmds = HashMap<String, CmdSpec>;
 
 /* The command specification's deserialization helper
@@ -52,6 +356,75 @@ struct CompletedCmd {
     name: String,
     started_at: Instant,
     exit_status: ExitStatus,
+    killed_by_watchdog: bool,
+}
+
+/* Picks the Stdio a child should be spawned with for a given sink: `Null`
+   is passed straight through to the child, but both `Inherit` and `File`
+   need a pipe since boss itself reads and redistributes the lines.
+*/
+fn stdio_for(sink: &Sink) -> Stdio {
+    match sink {
+        Sink::Null => Stdio::null(),
+        Sink::Inherit | Sink::File(_) => Stdio::piped(),
+    }
+}
+
+/* Moves the existing log file aside (as `<path>.1`) so each run starts
+   the named log fresh rather than appending to a previous invocation's
+   output forever.
+*/
+fn rotate_log_file(path: &str) -> std::io::Result<()> {
+    if Path::new(path).exists() {
+        std::fs::rename(path, format!("{}.1", path))
+    } else {
+        Ok(())
+    }
+}
+
+/* Drives one of a child's piped streams to completion: lines are either
+   appended to the command's log file or printed to boss's own console
+   prefixed with the command name, depending on the configured sink. When
+   `ready_match` is set (only ever on the stdout side, for a `ready_when:
+   log_match` command), the first line matching the regex fires the
+   readiness channel.
+*/
+async fn stream_output<R>(
+    name: String,
+    to_stderr: bool,
+    reader: R,
+    sink: Sink,
+    mut ready_match: Option<(Regex, Arc<watch::Sender<bool>>)>,
+) -> std::io::Result<()>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut log_file = match &sink {
+        Sink::File(path) => {
+            rotate_log_file(path)?;
+            Some(File::create(path).await?)
+        }
+        _ => None,
+    };
+
+    let mut lines = BufReader::new(reader).lines();
+    while let Some(line) = lines.next_line().await? {
+        if let Some((pattern, tx)) = &ready_match {
+            if pattern.is_match(&line) {
+                let _ = tx.broadcast(true);
+                ready_match = None;
+            }
+        }
+        match &mut log_file {
+            Some(file) => {
+                file.write_all(line.as_bytes()).await?;
+                file.write_all(b"\n").await?;
+            }
+            None if to_stderr => eprintln!("[{}] {}", name, line),
+            None => println!("[{}] {}", name, line),
+        }
+    }
+    Ok(())
 }
 
 /* Helper to spawn a process and return its future. The future provided by
@@ -60,35 +433,266 @@ struct CompletedCmd {
    identifier of the command. To do it, this maps the future to another
    future: an anonymous async function that takes ownership of the data to
    be saved and then awaits the "real" future (Tokio's Child). On completion,
-   the result is mapped to an instance of CompletedCmd.
+   the result is mapped to an instance of CompletedCmd. Alongside the child
+   itself, this also drives its piped stdout/stderr to completion so no
+   output is lost before the command is reported as finished.
+
+   When `timeout`/`kill_timeout` are set, the child's completion is raced
+   against a watchdog: the run is given `timeout` to finish on its own, then
+   sent SIGTERM, then given `kill_timeout` more before a SIGKILL. The term-
+   before-kill ordering is the point of the feature, so it's never skipped.
+
+   `ready_tx`, if given, is this command's own readiness channel: commands
+   that list it in `after` wait on the matching receiver. How (and when)
+   it fires depends on `cmd.ready_when`: immediately, for the default of
+   `None`; on a matching stdout line, for `LogMatch`; on a successful TCP
+   connect, polled from a background task, for `Tcp`; or on a clean exit,
+   for `Exit`.
 */
 fn get_cmd_future(
     name: &str,
     cmd: &mut CmdSpec,
+    ready_tx: Option<Arc<watch::Sender<bool>>>,
 ) -> Result<impl Future<Output = Result<CompletedCmd, std::io::Error>>, std::io::Error> {
     Command::new(&cmd.argv[0])
         .args(&cmd.argv[1..])
+        .stdout(stdio_for(&cmd.stdout))
+        .stderr(stdio_for(&cmd.stderr))
         .spawn()
-        .map(|r| {
-            cmd.pid = Some(Pid::from_raw(r.id() as i32));
-            let name = String::from(name);
+        .map(|mut child: Child| {
+            let pid = Pid::from_raw(child.id() as i32);
             let started_at = Instant::now();
+            cmd.pid = Some(pid);
+            cmd.started_at = Some(started_at);
+            cmd.manually_stopped = false;
+            let name = String::from(name);
+            let stdout = child.stdout.take();
+            let stderr = child.stderr.take();
+            let stdout_sink = cmd.stdout.clone();
+            let stderr_sink = cmd.stderr.clone();
+            let out_name = name.clone();
+            let err_name = name.clone();
+            let timeout = cmd.timeout;
+            let kill_timeout = cmd.kill_timeout;
+            let ready_when = cmd.ready_when.clone();
+
+            let stdout_ready_match = match &ready_when {
+                Some(ReadyWhen::LogMatch(pattern)) => match Regex::new(pattern) {
+                    Ok(re) => ready_tx.clone().map(|tx| (re, tx)),
+                    Err(e) => {
+                        eprintln!("{}: invalid ready_when log_match regex: {:?}", name, e);
+                        None
+                    }
+                },
+                _ => None,
+            };
+
+            match &ready_when {
+                None => {
+                    if let Some(tx) = &ready_tx {
+                        let _ = tx.broadcast(true);
+                    }
+                }
+                Some(ReadyWhen::Tcp(addr)) => {
+                    if let Some(tx) = ready_tx.clone() {
+                        let addr = addr.clone();
+                        tokio::spawn(async move {
+                            loop {
+                                if tokio::net::TcpStream::connect(addr.as_str()).await.is_ok() {
+                                    let _ = tx.broadcast(true);
+                                    return;
+                                }
+                                tokio::time::delay_for(Duration::from_millis(200)).await;
+                            }
+                        });
+                    }
+                }
+                Some(ReadyWhen::LogMatch(_)) | Some(ReadyWhen::Exit) => {
+                    /* LogMatch is tapped from stream_output's stdout pass above;
+                       Exit fires below, once the child has actually exited. */
+                }
+            }
+
             async move {
-                r.await.map(|exit_status| CompletedCmd {
+                let stdout_done = async {
+                    match stdout {
+                        Some(out) => {
+                            stream_output(out_name, false, out, stdout_sink, stdout_ready_match).await
+                        }
+                        None => Ok(()),
+                    }
+                };
+                let stderr_done = async {
+                    match stderr {
+                        Some(err) => stream_output(err_name, true, err, stderr_sink, None).await,
+                        None => Ok(()),
+                    }
+                };
+                let run = async { tokio::join!(child, stdout_done, stderr_done) };
+                tokio::pin!(run);
+
+                let (killed_by_watchdog, (exit_status, stdout_result, stderr_result)) =
+                    match timeout {
+                        None => (false, run.await),
+                        Some(timeout) => {
+                            tokio::select! {
+                                result = &mut run => (false, result),
+                                _ = tokio::time::delay_for(timeout) => {
+                                    let _ = kill(pid, Signal::SIGTERM);
+                                    match kill_timeout {
+                                        None => (true, run.await),
+                                        Some(kill_timeout) => {
+                                            tokio::select! {
+                                                result = &mut run => (true, result),
+                                                _ = tokio::time::delay_for(kill_timeout) => {
+                                                    let _ = kill(pid, Signal::SIGKILL);
+                                                    (true, run.await)
+                                                }
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    };
+                stdout_result?;
+                stderr_result?;
+                if let (Some(ReadyWhen::Exit), Ok(exit_status), Some(tx)) =
+                    (&ready_when, &exit_status, &ready_tx)
+                {
+                    if exit_status.success() {
+                        let _ = tx.broadcast(true);
+                    }
+                }
+                exit_status.map(|exit_status| CompletedCmd {
                     name,
                     started_at,
                     exit_status,
+                    killed_by_watchdog,
                 })
             }
         })
 }
 
-/* Read the commands to run from a YAML file into a commands collection.
+/* The top-level shape of `boss.yaml`: commands keyed by name, plus whatever
+   process-set-wide settings don't belong to any one command.
+*/
+#[derive(Deserialize)]
+struct Config {
+    /// If set, the control HTTP server listens here (see the `control` module).
+    listen_addr: Option<String>,
+    #[serde(flatten)]
+    cmds: Cmds,
+}
+
+/* Read the config, including the commands to run, from a YAML file.
 */
-fn read_cmds(path: &str) -> Result<Cmds> {
+fn read_config(path: &str) -> Result<Config> {
     Ok(serde_yaml::from_reader(std::fs::File::open(path)?)?)
 }
 
+/* Validates the `after` dependency graph (rejecting dangling references
+   and cycles with Kahn's algorithm) and returns a topological start
+   order. The returned order isn't used to actually drive spawning at
+   runtime -- that's done by the readiness watch channels below, which
+   respect the same graph as they resolve -- it's just cheap up-front
+   validation so a cycle is reported before anything is spawned.
+*/
+fn topo_order(cmds: &Cmds) -> Result<Vec<String>> {
+    let mut in_degree: HashMap<&str, usize> = cmds.keys().map(|name| (name.as_str(), 0)).collect();
+    let mut dependents: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (name, cmd) in cmds {
+        for dep in &cmd.after {
+            if !cmds.contains_key(dep) {
+                anyhow::bail!("{} depends on unknown command {:?} via `after`", name, dep);
+            }
+            *in_degree.get_mut(name.as_str()).unwrap() += 1;
+            dependents.entry(dep.as_str()).or_default().push(name.as_str());
+        }
+    }
+
+    let mut ready: Vec<&str> = in_degree
+        .iter()
+        .filter(|(_, &degree)| degree == 0)
+        .map(|(&name, _)| name)
+        .collect();
+    let mut order = Vec::with_capacity(cmds.len());
+    while let Some(name) = ready.pop() {
+        order.push(name.to_string());
+        if let Some(next) = dependents.get(name) {
+            for &dependent in next {
+                let degree = in_degree.get_mut(dependent).unwrap();
+                *degree -= 1;
+                if *degree == 0 {
+                    ready.push(dependent);
+                }
+            }
+        }
+    }
+
+    if order.len() != cmds.len() {
+        let stuck: Vec<&str> = cmds
+            .keys()
+            .map(String::as_str)
+            .filter(|name| !order.iter().any(|done| done == name))
+            .collect();
+        anyhow::bail!("dependency cycle detected among: {:?}", stuck);
+    }
+    Ok(order)
+}
+
+/* A `ready_when: exit` command is a one-shot (a migration, an init step)
+   whose readiness fires when it exits -- but every policy except `Never`
+   restarts on at least one of those exits (`Always` and `UnlessStopped`
+   restart on the clean exit that just fired readiness; `OnFailure`
+   restarts a failing one forever), which would crash-loop it right back
+   up via the backoff logic. Warn and coerce it to `Never` rather than
+   let the DB-migration-then-app scenario the config format exists for
+   turn into an infinite restart loop under any restart policy.
+*/
+fn coerce_oneshot_restart_policy(cmds: &mut Cmds) {
+    for (name, cmd) in cmds.iter_mut() {
+        if matches!(cmd.ready_when, Some(ReadyWhen::Exit)) && cmd.restart != RestartPolicy::Never {
+            eprintln!(
+                "{}: ready_when: exit with restart: {:?} would crash-loop a one-shot command; treating restart as never",
+                name, cmd.restart
+            );
+            cmd.restart = RestartPolicy::Never;
+        }
+    }
+}
+
+/* Resolves once a command's own readiness channel reports `true`. The
+   initial value is always `false`, so the first tick is skipped.
+*/
+async fn wait_for_ready(mut rx: watch::Receiver<bool>) {
+    while let Some(ready) = rx.recv().await {
+        if ready {
+            return;
+        }
+    }
+}
+
+/* Builds the future a not-yet-started command waits on before it's first
+   spawned: resolves, with the command's own name, once every dependency
+   listed in `after` has reported ready on its channel.
+*/
+fn wait_for_deps(
+    name: String,
+    after: &[String],
+    readiness_rx: &HashMap<String, watch::Receiver<bool>>,
+) -> Pin<Box<dyn Future<Output = String>>> {
+    let waits: Vec<_> = after
+        .iter()
+        .filter_map(|dep| readiness_rx.get(dep).cloned())
+        .map(wait_for_ready)
+        .collect();
+    Box::pin(async move {
+        futures::future::join_all(waits).await;
+        name
+    })
+}
+
 /* This is a closure for the filter_map function. It allows the futures
    Vec builder to return only a list of commands that were spawned successfully
    The failed ones are filtered out here along with a warning being printed.
@@ -117,18 +721,30 @@ fn only_ok(
   is done currently.
 */
 
-/* A convenience function for stopping processes
+/* A convenience function for stopping processes. The command stays in
+   `cmds`, marked as manually stopped (rather than being removed outright),
+   so a later `/start` or config reload can still find it; its pid is
+   cleared once the main loop handles the process's exit.
 */
-fn stop_process(cmd_name: &str, cmds: &mut Cmds) {
-    match cmds.get(cmd_name).unwrap().pid {
-        Some(pid) => match kill(pid, Signal::SIGTERM) {
-            Ok(()) => {
-                println!("stopping {} (pid: {})", cmd_name, pid);
-                cmds.remove(cmd_name);
+fn stop_process(cmd_name: &str, cmds: &mut Cmds, format: OutputFormat) {
+    match cmds.get_mut(cmd_name) {
+        Some(cmd) => {
+            cmd.manually_stopped = true;
+            match cmd.pid {
+                Some(pid) => match kill(pid, Signal::SIGTERM) {
+                    Ok(()) => emit_event(
+                        format,
+                        Some(cmd_name),
+                        Event::Stopped {
+                            reason: StopReason::Manual,
+                        },
+                    ),
+                    Err(e) => eprintln!("error signaling process: {:?}", e),
+                },
+                None => eprintln!("{} not running", cmd_name),
             }
-            Err(e) => eprintln!("error signaling process: {:?}", e),
-        },
-        None => eprintln!("{} not running", cmd_name),
+        }
+        None => eprintln!("{} not found", cmd_name),
     }
 }
 
@@ -140,134 +756,577 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         /// Path to configuration file
         #[structopt(short, long, default_value = "boss.yaml")]
         config_file: String,
+        /// Output format for lifecycle events: text or json
+        #[structopt(long, default_value = "text")]
+        format: OutputFormat,
+        /// Watch the config file and reload automatically on changes,
+        /// instead of requiring a SIGHUP
+        #[structopt(long)]
+        watch: bool,
     }
     let opt = Opt::from_args();
+    let format = opt.format;
 
-    let mut cmds = read_cmds(&opt.config_file)?;
+    let config = read_config(&opt.config_file)?;
+    let mut cmds = config.cmds;
+    coerce_oneshot_restart_policy(&mut cmds);
+    topo_order(&cmds)?;
 
     let mut hangups = signal(SignalKind::hangup())?;
+    let mut sigints = signal(SignalKind::interrupt())?;
+    let mut sigterms = signal(SignalKind::terminate())?;
+
+    /* One readiness watch channel per command: the sender fires once that
+       command satisfies its own `ready_when` condition, so that commands
+       which list it in `after` know when they may start. Every command
+       gets one, uniformly, since any of them could become a dependency
+       later via a config reload.
+    */
+    let mut readiness_tx: HashMap<String, Arc<watch::Sender<bool>>> = HashMap::new();
+    let mut readiness_rx: HashMap<String, watch::Receiver<bool>> = HashMap::new();
+    for name in cmds.keys() {
+        let (tx, rx) = watch::channel(false);
+        readiness_tx.insert(name.clone(), Arc::new(tx));
+        readiness_rx.insert(name.clone(), rx);
+    }
+
+    /* Commands waiting on their `after` dependencies to become ready
+       before they're started for the first time; see `wait_for_deps`.
+       Commands with no dependencies are spawned immediately below and go
+       straight into `all_futures`.
+    */
+    let mut pending_starts: FuturesUnordered<Pin<Box<dyn Future<Output = String>>>> =
+        FuturesUnordered::new();
 
     /* All commands become part of a FutureUnordered stream which is populated
-    in two places: from a Vec of futures here, at startup, and by pushing
-    individual futures later, after the processes finish. The specific
-    types are inferred by the return signatures of the `get_cmd_future()
-    and `only_ok()` functions. Even though the types look the same, they
-    are two different types in view of the type system.  Because of this,
-    the `Either` wrapper type must be used to accomodate both of them. The
-    other way to handle the type variability is via using BoxFutures but
-    Either doesn't involve a heap allocation.
+    in three places: directly here at startup for commands with no `after`
+    dependencies, from `pending_starts` once a gated command's dependencies
+    are ready, and by pushing individual futures later, after processes
+    finish. The specific types are inferred by the return signatures of the
+    `get_cmd_future()` and `only_ok()` functions. Even though the types look
+    the same, they are two different types in view of the type system.
+    Because of this, the `Either` wrapper type must be used to accomodate
+    both of them. The other way to handle the type variability is via using
+    BoxFutures but Either doesn't involve a heap allocation.
     */
-    let mut all_futures: FuturesUnordered<_> = cmds
-        .iter_mut()
-        .map(|(name, cmd)| get_cmd_future(name, cmd))
-        .filter_map(only_ok)
-        .map(|ok| Either::Left(ok))
-        .collect();
+    let mut all_futures: FuturesUnordered<_> = FuturesUnordered::new();
+    for (name, cmd) in cmds.iter_mut() {
+        if cmd.after.is_empty() {
+            if let Some(spawned) = only_ok(get_cmd_future(name, cmd, readiness_tx.get(name).cloned())) {
+                emit_event(format, Some(name), Event::Started);
+                all_futures.push(Either::Left(spawned));
+            }
+        } else {
+            pending_starts.push(wait_for_deps(name.clone(), &cmd.after, &readiness_rx));
+        }
+    }
+
+    /* Commands whose restart has been delayed by the crash-loop backoff.
+       Each entry just sleeps for its computed delay and yields the command's
+       name, at which point the main loop re-spawns it via get_cmd_future.
+    */
+    let mut pending_restarts: FuturesUnordered<Pin<Box<dyn Future<Output = String>>>> =
+        FuturesUnordered::new();
+
+    /* The control HTTP server (see the `control` module) runs as its own
+       task and talks to the main loop over this channel, so `cmds` only
+       ever has one owner. It's only actually spawned when `listen_addr`
+       is configured, but the channel always exists so the select! arm
+       below is uniform either way.
+    */
+    let (control_tx, mut control_rx) = mpsc::unbounded_channel::<control::ControlMsg>();
+    if let Some(listen_addr) = &config.listen_addr {
+        let addr: SocketAddr = listen_addr.parse()?;
+        tokio::spawn(control::run(addr, control_tx.clone()));
+    }
+
+    /* When --watch is given, a RecommendedWatcher debounces filesystem
+       events for us (editors often write-truncate then rename) and
+       delivers at most one per 500ms window on its own std::sync::mpsc
+       channel. A blocking thread forwards those onto this async channel
+       so the change can be picked up by the select! loop below; the
+       watcher itself is moved into that thread so it isn't dropped (and
+       stopped) while still in scope.
+
+       Watching the config file's path directly only follows its current
+       inode: an editor's write-truncate-then-rename replace leaves the
+       watch attached to the old (now unlinked) inode, so only the first
+       edit is ever delivered. Watching the parent directory instead keeps
+       working across that replace; events are filtered down to the
+       config file's own name below.
+    */
+    let config_file_name = Path::new(&opt.config_file)
+        .file_name()
+        .map(|n| n.to_owned())
+        .ok_or_else(|| anyhow::anyhow!("{}: not a file path", opt.config_file))?;
+    let watch_dir = Path::new(&opt.config_file)
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."))
+        .to_path_buf();
+    let (watch_tx, mut watch_rx) = mpsc::unbounded_channel::<DebouncedEvent>();
+    if opt.watch {
+        let (notify_tx, notify_rx) = std::sync::mpsc::channel();
+        let mut watcher: RecommendedWatcher =
+            notify::watcher(notify_tx, Duration::from_millis(500))?;
+        watcher.watch(&watch_dir, RecursiveMode::NonRecursive)?;
+        std::thread::spawn(move || {
+            let _watcher = watcher;
+            while let Ok(event) = notify_rx.recv() {
+                if watch_tx.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    /* Re-reads the config file and applies the diff between it and the
+       current `cmds`: stops removed commands, starts added ones, and
+       restarts ones whose argv changed. Shared between the SIGHUP handler,
+       the control API's `/reload` and the --watch file watcher, as a macro
+       rather than a function because `all_futures`'s element type is opaque
+       (see the TODO above `stop_process`).
+    */
+    macro_rules! reload_config {
+        () => {
+            match read_config(&opt.config_file) {
+                Ok(new_config) => {
+                    let mut new_cmds = new_config.cmds;
+                    coerce_oneshot_restart_policy(&mut new_cmds);
+                    let cur_cmd_names: HashSet<String> = cmds.keys().cloned().collect();
+                    let updated_cmd_names: HashSet<String> = new_cmds.keys().cloned().collect();
+                    let mut added = Vec::new();
+                    let mut removed = Vec::new();
+                    let mut changed = Vec::new();
+
+                    /* Stop commands that have been removed from the list. */
+                    for cmd_name in cur_cmd_names.difference(&updated_cmd_names) {
+                        removed.push(cmd_name.clone());
+                        stop_process(cmd_name, &mut cmds, format);
+                        cmds.remove(cmd_name);
+                    }
+
+                    /* Start newly-added commands. A command added via reload
+                       isn't gated on `after`, since the dependency graph is
+                       only enforced at startup; it gets a fresh readiness
+                       channel of its own in case something later depends on
+                       it.
+                    */
+                    for cmd_name in updated_cmd_names.difference(&cur_cmd_names) {
+                        let mut cmd = new_cmds.remove(cmd_name).unwrap();
+                        let ready_tx = readiness_tx
+                            .entry(cmd_name.clone())
+                            .or_insert_with(|| Arc::new(watch::channel(false).0))
+                            .clone();
+                        match get_cmd_future(cmd_name, &mut cmd, Some(ready_tx)) {
+                            Ok(spawned_child) => {
+                                added.push(cmd_name.clone());
+                                emit_event(format, Some(cmd_name), Event::Started);
+                                all_futures.push(Either::Right(spawned_child));
+                                cmds.insert(cmd_name.to_string(), cmd);
+                            }
+                            Err(e) => println!("spawn failed: {:?}", e),
+                        }
+                    }
+
+                    /* Stop commands that have been updated, re-inserting the
+                       new argument vector back into the set. These will be
+                       restarted with the revised args when the current ones finish.
+                    */
+                    for cmd_name in cur_cmd_names.intersection(&updated_cmd_names) {
+                        if cmds.get(cmd_name).unwrap().argv != new_cmds.get(cmd_name).unwrap().argv {
+                            changed.push(cmd_name.clone());
+                            let cmd = new_cmds.remove(cmd_name).unwrap();
+                            stop_process(cmd_name, &mut cmds, format);
+                            cmds.insert(cmd_name.to_string(), cmd);
+                        }
+                    }
+                    emit_event(format, None, Event::ConfigReloaded { added, removed, changed });
+                }
+                Err(e) => eprintln!("error re-reading config: {:?}", e),
+            }
+        };
+    }
+
+    /* Stops every running command and drains `all_futures`, for a clean
+       shutdown on SIGINT/SIGTERM. SIGTERM is sent in reverse dependency
+       order (the `after` graph in reverse: dependents before what they
+       depend on), then each process is given `SHUTDOWN_GRACE_PERIOD` to
+       exit on its own before a SIGKILL sweep. Because this drains
+       `all_futures` directly rather than going back through the
+       `completed_process` arm below, nothing here gets re-spawned --
+       there's no separate "shutting down" flag to check.
+    */
+    macro_rules! graceful_shutdown {
+        () => {{
+            let order = topo_order(&cmds).unwrap_or_else(|_| cmds.keys().cloned().collect());
+            for name in order.iter().rev() {
+                if let Some(pid) = cmds.get(name).and_then(|cmd| cmd.pid) {
+                    let _ = kill(pid, Signal::SIGTERM);
+                }
+            }
+
+            /* `cmds` itself is left alone here (no point clearing `pid`
+               fields right before the process exits); only the pids
+               already read above are needed to drive the kill escalation.
+            */
+            let drain = async {
+                while let Some(result) = all_futures.next().await {
+                    if let Ok(child) = result {
+                        emit_event(format, Some(&child.name), Event::Stopped {
+                            reason: StopReason::Manual,
+                        });
+                    }
+                }
+            };
+            tokio::pin!(drain);
+            tokio::select! {
+                _ = &mut drain => {}
+                _ = tokio::time::delay_for(SHUTDOWN_GRACE_PERIOD) => {
+                    for name in order.iter().rev() {
+                        if let Some(pid) = cmds.get(name).and_then(|cmd| cmd.pid) {
+                            let _ = kill(pid, Signal::SIGKILL);
+                        }
+                    }
+                    drain.await;
+                }
+            }
+        }};
+    }
 
     loop {
         tokio::select! {
             /* Process the receipt of the HUP signal. */
             _ = hangups.recv() => {
-                match read_cmds(&opt.config_file) {
-                    Ok(mut new_cmds) => {
-                        let cur_cmd_names: HashSet<String> = cmds.keys().cloned().collect();
-                        let updated_cmd_names: HashSet<String> = new_cmds.keys().cloned().collect();
-                        let mut changes = false;
-
-                        /* Stop commands that have been removed from the list. */
-                        for cmd_name in cur_cmd_names.difference(&updated_cmd_names) {
-                            changes = true;
-                            stop_process(cmd_name, &mut cmds);
-                        }
+                reload_config!();
+            },
+
+            /* Ctrl-C or a plain `kill`: drain everything cleanly instead of
+               leaving children orphaned or abruptly signaled by the
+               terminal's process-group defaults.
+            */
+            _ = sigints.recv() => {
+                graceful_shutdown!();
+                break;
+            },
+            _ = sigterms.recv() => {
+                graceful_shutdown!();
+                break;
+            },
 
-                        /* Start newly-added commands. */
-                        for cmd_name in updated_cmd_names.difference(&cur_cmd_names) {
-                            changes = true;
-                            let mut cmd = new_cmds.remove(cmd_name).unwrap();
-                            match get_cmd_future(cmd_name, &mut cmd) {
-                                Ok(spawned_child) => {
-                                    println!("starting {}", cmd_name);
-                                    all_futures.push(Either::Right(spawned_child));
-                                    cmds.insert(cmd_name.to_string(), cmd);
+            /* Requests from the control HTTP server: status queries and
+               remote equivalents of SIGHUP/stop/start.
+            */
+            Some(msg) = control_rx.recv() => {
+                match msg {
+                    control::ControlMsg::Status(reply) => {
+                        let statuses = cmds.iter().map(|(name, cmd)| control::CmdStatus {
+                            name: name.clone(),
+                            pid: cmd.pid.map(|pid| pid.as_raw()),
+                            uptime_secs: cmd.started_at.map(|t| t.elapsed().as_secs()),
+                            restart_count: cmd.restart_count,
+                            last_exit_code: cmd.last_exit_code,
+                        }).collect();
+                        let _ = reply.send(statuses);
+                    }
+                    control::ControlMsg::Reload => {
+                        reload_config!();
+                    }
+                    control::ControlMsg::Stop(name) => {
+                        stop_process(&name, &mut cmds, format);
+                    }
+                    control::ControlMsg::Start(name) => {
+                        match cmds.get_mut(&name) {
+                            Some(cmd) if cmd.pid.is_none() => {
+                                cmd.manually_stopped = false;
+                                match get_cmd_future(&name, cmd, readiness_tx.get(&name).cloned()) {
+                                    Ok(spawned_child) => {
+                                        emit_event(format, Some(&name), Event::Started);
+                                        all_futures.push(Either::Right(spawned_child));
+                                    }
+                                    Err(e) => println!("spawn failed: {:?}", e),
                                 }
-                                Err(e) => println!("spawn failed: {:?}", e),
                             }
+                            Some(_) => eprintln!("{} already running", name),
+                            None => eprintln!("{} not found", name),
                         }
+                    }
+                }
+            },
 
-                        /* Stop commands that have been updated, re-inserting the
-                           new argument vector back into the set. These will be
-                           restarted with the revised args when the current ones finish.
-                        */
-                        for cmd_name in cur_cmd_names.intersection(&updated_cmd_names) {
-                            if cmds.get(cmd_name).unwrap().argv != new_cmds.get(cmd_name).unwrap().argv {
-                                changes = true;
-                                let cmd = new_cmds.remove(cmd_name).unwrap();
-                                stop_process(cmd_name, &mut cmds);
-                                cmds.insert(cmd_name.to_string(), cmd);
-                            }
+            /* The config file changed on disk (--watch only); reload the
+               same way a SIGHUP would. The directory watch (see above)
+               delivers events for every file in it, so only act on ones
+               naming the config file itself; a Rename's `to` path is the
+               post-replace name, so check both sides of it.
+            */
+            Some(event) = watch_rx.recv(), if opt.watch => {
+                let names_config_file = |p: &std::path::PathBuf| p.file_name() == Some(config_file_name.as_os_str());
+                let is_config_file_event = match &event {
+                    DebouncedEvent::Write(p) | DebouncedEvent::Create(p) => names_config_file(p),
+                    DebouncedEvent::Rename(from, to) => names_config_file(from) || names_config_file(to),
+                    _ => false,
+                };
+                if is_config_file_event {
+                    reload_config!();
+                }
+            },
+
+            /* A backoff delay for a previously crash-looping command has
+               elapsed; actually spawn it now.
+            */
+            Some(restart_name) = pending_restarts.next(), if !pending_restarts.is_empty() => {
+                if let Some(cmd) = cmds.get_mut(&restart_name) {
+                    match get_cmd_future(&restart_name, cmd, readiness_tx.get(&restart_name).cloned()) {
+                        Ok(spawned_child) => {
+                            emit_event(format, Some(&restart_name), Event::Started);
+                            all_futures.push(Either::Right(spawned_child));
+                        }
+                        Err(e) => println!("spawn failed: {:?}", e),
+                    }
+                }
+            },
+
+            /* A command that was waiting on its `after` dependencies has
+               had all of them report ready; start it now.
+            */
+            Some(name) = pending_starts.next(), if !pending_starts.is_empty() => {
+                if let Some(cmd) = cmds.get_mut(&name) {
+                    match get_cmd_future(&name, cmd, readiness_tx.get(&name).cloned()) {
+                        Ok(spawned_child) => {
+                            emit_event(format, Some(&name), Event::Started);
+                            all_futures.push(Either::Right(spawned_child));
                         }
-                        if !changes { println!("no changes to commands") }
-                   },
-                   Err(e) => eprintln!("error re-reading config: {:?}", e),
+                        Err(e) => println!("spawn failed: {:?}", e),
+                    }
                 }
             },
 
             /* Process command terminations. The resolved future here is the
                next item of the FuturesUnordered stream. These items are a
-               two level construct: an Option that contains a Result.
+               two level construct: an Option that contains a Result. The
+               guard matters: an empty `all_futures` resolves to `None`
+               immediately, which would otherwise race ahead of a command
+               that's merely sleeping in `pending_restarts`/`pending_starts`
+               and report `AllFinished` while it's still due to come back.
             */
-            completed_process = all_futures.next() => {
+            completed_process = all_futures.next(), if !all_futures.is_empty() => {
                 /* The first level (the Option) is either an actual Result of one of
-                   the Child futures, or the None value indicating end of stream. In
-                   practice, this would only be reached if the user removed all the
-                   commands from the config, which is unlikely.
+                   the Child futures, or the None value indicating end of stream; the
+                   latter can't actually happen here thanks to the guard above, which
+                   only polls this arm when the stream has something to give.
                 */
-                match completed_process {
-                    Some(result) => {
-                       /* The second level (the Result) is the final disposition of the process.
-                          Both zero and non-zero exit statuses come through the Ok case, so it's
-                          unclear how the Err case happens. But it's possible to get a
-                          std::io::Error here.
-                       */
-                        match result {
-                            Ok(child) => {
-                                let result = match child.exit_status.code() {
-                                    Some(code) => format!("exited with status {}", code),
-                                    None => format!("terminated by signal")
-                                };
-                                println!(
-                                    "{}: {}, after {} sec.",
-                                    child.name,
-                                    result,
-                                    child.started_at.elapsed().as_secs(),
-                                );
-                                match cmds.get_mut(&child.name) {
-                                    Some(cmd) => match get_cmd_future(&child.name, cmd) {
-                                        Ok(spawned_child) => {
-                                            println!("restarting: {}", child.name);
-                                            all_futures.push(Either::Right(spawned_child))
+                if let Some(result) = completed_process {
+                    /* The second level (the Result) is the final disposition of the process.
+                       Both zero and non-zero exit statuses come through the Ok case, so it's
+                       unclear how the Err case happens. But it's possible to get a
+                       std::io::Error here.
+                    */
+                    match result {
+                        Ok(child) => {
+                            emit_event(format, Some(&child.name), Event::Exited {
+                                code: child.exit_status.code(),
+                                signal: child.exit_status.signal(),
+                                duration_secs: child.started_at.elapsed().as_secs(),
+                                killed_by_watchdog: child.killed_by_watchdog,
+                            });
+                            if let Some(cmd) = cmds.get_mut(&child.name) {
+                                cmd.last_exit_code = child.exit_status.code();
+                            }
+                            match cmds.get_mut(&child.name) {
+                                Some(cmd) if should_restart(cmd.restart, &child.exit_status, cmd.manually_stopped) => {
+                                    let ran_long_enough =
+                                        child.started_at.elapsed() >= cmd.success_threshold();
+                                    cmd.restart_count =
+                                        if ran_long_enough { 0 } else { cmd.restart_count + 1 };
+
+                                    if cmd.max_restarts.map_or(false, |max| cmd.restart_count > max) {
+                                        emit_event(format, Some(&child.name), Event::Stopped {
+                                            reason: StopReason::GaveUp,
+                                        });
+                                        cmd.pid = None;
+                                    } else if ran_long_enough {
+                                        match get_cmd_future(&child.name, cmd, readiness_tx.get(&child.name).cloned()) {
+                                            Ok(spawned_child) => {
+                                                emit_event(format, Some(&child.name), Event::Restarting {
+                                                    attempt: cmd.restart_count,
+                                                    delay_secs: 0,
+                                                });
+                                                all_futures.push(Either::Right(spawned_child));
+                                            }
+                                            Err(e) => println!("spawn failed: {:?}", e),
                                         }
-                                        Err(e) => println!("spawn failed: {:?}", e),
-                                    },
-                                    None => println!("final invocation of : {}", child.name),
+                                    } else {
+                                        let delay = cmd.backoff_delay();
+                                        cmd.pid = None;
+                                        emit_event(format, Some(&child.name), Event::Restarting {
+                                            attempt: cmd.restart_count,
+                                            delay_secs: delay.as_secs(),
+                                        });
+                                        let restart_name = child.name.clone();
+                                        pending_restarts.push(Box::pin(async move {
+                                            tokio::time::delay_for(delay).await;
+                                            restart_name
+                                        }));
+                                    }
                                 }
+                                Some(cmd) => {
+                                    /* A manual stop already emitted its own
+                                       `Stopped{Manual}` event when the signal
+                                       was sent; don't report it again here.
+                                    */
+                                    if !cmd.manually_stopped {
+                                        emit_event(format, Some(&child.name), Event::Stopped {
+                                            reason: StopReason::Policy,
+                                        });
+                                    }
+                                    cmd.pid = None;
+                                }
+                                None => emit_event(format, Some(&child.name), Event::Stopped {
+                                    reason: StopReason::Manual,
+                                }),
                             }
-                            /* TODO: the Error variant doesn't contain the command
-                               information (specifically the name), it wouldn't be
-                               possible to restart here, or print the name of which
-                               command failed. Need to map the Error associated type
-                               for the future.
-                            */
-                            Err(e) => eprintln!("error with process spawn: {:?}", e),
                         }
-                    }
-                    None => {
-                        println!("All processes finished");
-                        break;
+                        /* TODO: the Error variant doesn't contain the command
+                           information (specifically the name), it wouldn't be
+                           possible to restart here, or print the name of which
+                           command failed. Need to map the Error associated type
+                           for the future.
+                        */
+                        Err(e) => eprintln!("error with process spawn: {:?}", e),
                     }
                 }
             },
         }
+
+        /* Nothing left running, sleeping on a backoff, or waiting on a
+           dependency: there's nothing further this loop could ever do.
+        */
+        if all_futures.is_empty() && pending_restarts.is_empty() && pending_starts.is_empty() {
+            emit_event(format, None, Event::AllFinished);
+            break;
+        }
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_cmd() -> CmdSpec {
+        serde_yaml::from_str("argv: 'true'").unwrap()
+    }
+
+    #[test]
+    fn rotate_log_file_moves_existing_log_aside() {
+        let dir = std::env::temp_dir().join(format!("boss-test-rotate-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.log");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, b"previous run").unwrap();
+
+        rotate_log_file(path).unwrap();
+
+        assert!(!Path::new(path).exists());
+        assert_eq!(std::fs::read(format!("{}.1", path)).unwrap(), b"previous run");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rotate_log_file_is_a_no_op_when_nothing_to_rotate() {
+        let dir = std::env::temp_dir().join(format!("boss-test-rotate-noop-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.log");
+        let path = path.to_str().unwrap();
+
+        rotate_log_file(path).unwrap();
+
+        assert!(!Path::new(path).exists());
+        assert!(!Path::new(&format!("{}.1", path)).exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn should_restart_always_ignores_exit_status_but_honors_manual_stop() {
+        let failure = ExitStatus::from_raw(256); // exit code 1
+        assert!(should_restart(RestartPolicy::Always, &failure, false));
+        assert!(!should_restart(RestartPolicy::Always, &failure, true));
+    }
+
+    #[test]
+    fn should_restart_never_never_restarts() {
+        let success = ExitStatus::from_raw(0);
+        assert!(!should_restart(RestartPolicy::Never, &success, false));
+    }
+
+    #[test]
+    fn should_restart_unless_stopped_honors_manual_stop() {
+        let success = ExitStatus::from_raw(0);
+        assert!(should_restart(RestartPolicy::UnlessStopped, &success, false));
+        assert!(!should_restart(RestartPolicy::UnlessStopped, &success, true));
+    }
+
+    #[test]
+    fn should_restart_on_failure_only_restarts_on_nonzero_exit() {
+        let success = ExitStatus::from_raw(0);
+        let failure = ExitStatus::from_raw(256);
+        assert!(!should_restart(RestartPolicy::OnFailure, &success, false));
+        assert!(should_restart(RestartPolicy::OnFailure, &failure, false));
+        assert!(!should_restart(RestartPolicy::OnFailure, &failure, true));
+    }
+
+    #[test]
+    fn backoff_delay_doubles_with_each_restart_and_caps_at_max() {
+        let mut cmd = test_cmd();
+        cmd.backoff_base = Some(Duration::from_secs(1));
+        cmd.backoff_max = Some(Duration::from_secs(10));
+
+        cmd.restart_count = 1;
+        assert_eq!(cmd.backoff_delay(), Duration::from_secs(1));
+        cmd.restart_count = 2;
+        assert_eq!(cmd.backoff_delay(), Duration::from_secs(2));
+        cmd.restart_count = 3;
+        assert_eq!(cmd.backoff_delay(), Duration::from_secs(4));
+        cmd.restart_count = 10;
+        assert_eq!(cmd.backoff_delay(), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn backoff_delay_defaults_when_unset() {
+        let cmd = test_cmd();
+        assert_eq!(cmd.backoff_delay(), DEFAULT_BACKOFF_BASE);
+    }
+
+    fn cmds_with_after(edges: &[(&str, &[&str])]) -> Cmds {
+        edges
+            .iter()
+            .map(|(name, after)| {
+                let mut cmd = test_cmd();
+                cmd.after = after.iter().map(|s| s.to_string()).collect();
+                (name.to_string(), cmd)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn topo_order_respects_after_dependencies() {
+        let cmds = cmds_with_after(&[("a", &[]), ("b", &["a"]), ("c", &["b"])]);
+        let order = topo_order(&cmds).unwrap();
+        let pos = |name: &str| order.iter().position(|n| n == name).unwrap();
+        assert!(pos("a") < pos("b"));
+        assert!(pos("b") < pos("c"));
+    }
+
+    #[test]
+    fn topo_order_rejects_unknown_dependency() {
+        let cmds = cmds_with_after(&[("a", &["nonexistent"])]);
+        assert!(topo_order(&cmds).is_err());
+    }
+
+    #[test]
+    fn topo_order_rejects_cycles() {
+        let cmds = cmds_with_after(&[("a", &["b"]), ("b", &["a"])]);
+        assert!(topo_order(&cmds).is_err());
+    }
+}
@@ -0,0 +1,95 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde::Serialize;
+use tokio::sync::{mpsc, oneshot};
+
+/* A snapshot of one command's state, as returned by `GET /status`.
+*/
+#[derive(Serialize)]
+pub struct CmdStatus {
+    pub name: String,
+    pub pid: Option<i32>,
+    pub uptime_secs: Option<u64>,
+    pub restart_count: u32,
+    pub last_exit_code: Option<i32>,
+}
+
+/* Requests the control server makes of the supervisor. The server never
+   touches `Cmds` itself: every action is funneled through this channel so
+   the main select! loop remains the single owner of process state.
+*/
+pub enum ControlMsg {
+    Status(oneshot::Sender<Vec<CmdStatus>>),
+    Reload,
+    Stop(String),
+    Start(String),
+}
+
+fn not_found() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(Body::from("not found\n"))
+        .unwrap()
+}
+
+fn internal_error() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .body(Body::from("internal error\n"))
+        .unwrap()
+}
+
+async fn handle(
+    req: Request<Body>,
+    tx: mpsc::UnboundedSender<ControlMsg>,
+) -> Result<Response<Body>, Infallible> {
+    let path: Vec<&str> = req.uri().path().trim_matches('/').split('/').collect();
+    let response = match (req.method(), path.as_slice()) {
+        (&Method::GET, ["status"]) => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            if tx.send(ControlMsg::Status(reply_tx)).is_err() {
+                return Ok(internal_error());
+            }
+            match reply_rx.await {
+                Ok(statuses) => match serde_json::to_string(&statuses) {
+                    Ok(body) => Response::new(Body::from(body)),
+                    Err(_) => internal_error(),
+                },
+                Err(_) => internal_error(),
+            }
+        }
+        (&Method::POST, ["reload"]) => {
+            let _ = tx.send(ControlMsg::Reload);
+            Response::new(Body::from("reloading\n"))
+        }
+        (&Method::POST, [name, "stop"]) => {
+            let _ = tx.send(ControlMsg::Stop((*name).to_string()));
+            Response::new(Body::from("stopping\n"))
+        }
+        (&Method::POST, [name, "start"]) => {
+            let _ = tx.send(ControlMsg::Start((*name).to_string()));
+            Response::new(Body::from("starting\n"))
+        }
+        _ => not_found(),
+    };
+    Ok(response)
+}
+
+/* Runs the control HTTP server until the process exits, as its own tokio
+   task alongside the main supervisor loop. It only translates HTTP
+   requests into `ControlMsg`s and relays `/status` replies back; it never
+   mutates `Cmds` directly.
+*/
+pub async fn run(addr: SocketAddr, tx: mpsc::UnboundedSender<ControlMsg>) {
+    let make_svc = make_service_fn(move |_conn| {
+        let tx = tx.clone();
+        async move { Ok::<_, Infallible>(service_fn(move |req| handle(req, tx.clone()))) }
+    });
+    println!("control server listening on http://{}", addr);
+    if let Err(e) = Server::bind(&addr).serve(make_svc).await {
+        eprintln!("control server error: {:?}", e);
+    }
+}